@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A small arithmetic expression AST with variables, `let` bindings, and
+/// `if`/`else`, used to drive `CodeGen::compile_expr` instead of the
+/// hardcoded demo kernels.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    IntLit(i64),
+    FloatLit(f64),
+    Var(String),
+    BinOp(BinOp, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Let(String, Box<Expr>, Box<Expr>),
+}
+
+/// Operators a `BinOp` expression can lower to. Arithmetic ops map to
+/// `build_int_*`/`build_float_*`; comparisons map to `build_int_compare`/
+/// `build_float_compare` with the appropriate predicate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+/// A scoped symbol table: a stack of scopes, pushed/popped around `let`
+/// bindings so a binding only shadows names for the lifetime of its body.
+pub struct Env<K, V> {
+    scopes: Vec<HashMap<K, V>>,
+}
+
+impl<K: Eq + Hash, V: Clone> Env<K, V> {
+    pub fn new() -> Self {
+        Env {
+            scopes: vec![HashMap::new()],
+        }
+    }
+
+    pub fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    pub fn define(&mut self, name: K, value: V) {
+        self.scopes
+            .last_mut()
+            .expect("Env must always have at least one scope")
+            .insert(name, value);
+    }
+
+    pub fn get(&self, name: &K) -> Option<&V> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> Default for Env<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,30 @@
+// Playground binary: several alternative code paths (e.g. `jit_compile_sum`,
+// unused `VecOp`/`ElemTy` variants) are kept around for reference even though
+// `main` only exercises a subset of them.
+#![allow(dead_code)]
+
+mod expr;
+mod types;
+
+use expr::{BinOp, Env, Expr};
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::{ExecutionEngine, JitFunction};
 use inkwell::module::Module;
-use inkwell::types::VectorType;
-use inkwell::{AddressSpace, OptimizationLevel};
+use inkwell::targets::{
+    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine, TargetTriple,
+};
+use inkwell::types::{BasicMetadataTypeEnum, BasicType, BasicTypeEnum, VectorType};
+use inkwell::values::{BasicValue, BasicValueEnum, FunctionValue};
+use inkwell::{AddressSpace, FloatPredicate, IntPredicate, OptimizationLevel};
 use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Convenience type alias for the runtime-length vectorized `sum` with a
+/// scalar remainder tail.
+type SumNFunc = unsafe extern "C" fn(*const f64, *const f64, *mut f64, u64);
 
 /// Convenience type alias for the `sum` function.
 ///
@@ -12,15 +32,158 @@ use std::error::Error;
 /// do `unsafe` operations internally.
 type SumFunc = unsafe extern "C" fn(u64, u64, u64) -> u64;
 
+/// The element-wise operation a vector kernel should perform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VecOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// The scalar element type a vector kernel operates over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ElemTy {
+    F32,
+    F64,
+    I32,
+    I64,
+}
+
+impl ElemTy {
+    fn is_float(&self) -> bool {
+        matches!(self, ElemTy::F32 | ElemTy::F64)
+    }
+}
+
+/// A JIT'd vector kernel, specialized to the element type it was built for.
+enum VectorKernelFn<'ctx> {
+    F32(JitFunction<'ctx, unsafe extern "C" fn(*const f32, *const f32, *mut f32) -> f32>),
+    F64(JitFunction<'ctx, unsafe extern "C" fn(*const f64, *const f64, *mut f64) -> f64>),
+    I32(JitFunction<'ctx, unsafe extern "C" fn(*const i32, *const i32, *mut i32) -> i32>),
+    I64(JitFunction<'ctx, unsafe extern "C" fn(*const i64, *const i64, *mut i64) -> i64>),
+}
+
+/// One kernel to generate as part of a `CodeGen::compile_parallel` batch.
+#[derive(Clone, Debug)]
+struct KernelSpec {
+    name: String,
+    op: VecOp,
+    elem_ty: ElemTy,
+    width: u32,
+}
+
+/// The scalar `BasicTypeEnum` backing `elem_ty`.
+fn elem_basic_type(context: &Context, elem_ty: ElemTy) -> BasicTypeEnum<'_> {
+    match elem_ty {
+        ElemTy::F32 => context.f32_type().into(),
+        ElemTy::F64 => context.f64_type().into(),
+        ElemTy::I32 => context.i32_type().into(),
+        ElemTy::I64 => context.i64_type().into(),
+    }
+}
+
+/// The `<width x elem_ty>` vector type for `elem_ty`.
+fn elem_vec_type<'ctx>(context: &'ctx Context, elem_ty: ElemTy, width: u32) -> VectorType<'ctx> {
+    match elem_ty {
+        ElemTy::F32 => context.f32_type().vec_type(width),
+        ElemTy::F64 => context.f64_type().vec_type(width),
+        ElemTy::I32 => context.i32_type().vec_type(width),
+        ElemTy::I64 => context.i64_type().vec_type(width),
+    }
+}
+
+/// Emit `name(x, y, z) { z[i] = x[i] op y[i] for i in 0..width }` as a
+/// `<width x elem_ty>` vector op. Free function so it's shared between
+/// `CodeGen::jit_vector_kernel` and `CodeGen::compile_parallel`.
+fn build_vector_kernel<'ctx>(
+    context: &'ctx Context,
+    module: &Module<'ctx>,
+    builder: &Builder<'ctx>,
+    name: &str,
+    op: VecOp,
+    elem_ty: ElemTy,
+    width: u32,
+) -> Option<FunctionValue<'ctx>> {
+    let elem_basic_ty = elem_basic_type(context, elem_ty);
+    let vec_ty = elem_vec_type(context, elem_ty, width);
+    let elem_ptr_ty = elem_basic_ty.ptr_type(AddressSpace::default());
+    let fn_type = elem_basic_ty.fn_type(
+        &[elem_ptr_ty.into(), elem_ptr_ty.into(), elem_ptr_ty.into()],
+        false,
+    );
+    let function = module.add_function(name, fn_type, None);
+    let basic_block = context.append_basic_block(function, "entry");
+
+    builder.position_at_end(basic_block);
+
+    let x = function.get_nth_param(0)?.into_pointer_value();
+    let y = function.get_nth_param(1)?.into_pointer_value();
+    let z = function.get_nth_param(2)?.into_pointer_value();
+
+    let mut x_vals = vec![];
+    let mut y_vals = vec![];
+    for i in 0..width {
+        let idx = context.i64_type().const_int(i as u64, false);
+        let x_ptr = unsafe { builder.build_gep(x, &[idx], "gep") };
+        let x_val = builder.build_load(x_ptr, "load");
+        let y_ptr = unsafe { builder.build_gep(y, &[idx], "gep") };
+        let y_val = builder.build_load(y_ptr, "load");
+        x_vals.push((idx, x_val));
+        y_vals.push((idx, y_val))
+    }
+
+    let mut z_x = vec_ty.const_zero();
+    for (i, x) in x_vals {
+        z_x = builder.build_insert_element(z_x, x, i, "insert");
+    }
+
+    let mut z_y = vec_ty.const_zero();
+    for (i, y) in y_vals {
+        z_y = builder.build_insert_element(z_y, y, i, "insert");
+    }
+
+    let result = match (op, elem_ty.is_float()) {
+        (VecOp::Add, true) => builder.build_float_add(z_x, z_y, "vec_add"),
+        (VecOp::Sub, true) => builder.build_float_sub(z_x, z_y, "vec_sub"),
+        (VecOp::Mul, true) => builder.build_float_mul(z_x, z_y, "vec_mul"),
+        (VecOp::Div, true) => builder.build_float_div(z_x, z_y, "vec_div"),
+        (VecOp::Add, false) => builder.build_int_add(z_x, z_y, "vec_add"),
+        (VecOp::Sub, false) => builder.build_int_sub(z_x, z_y, "vec_sub"),
+        (VecOp::Mul, false) => builder.build_int_mul(z_x, z_y, "vec_mul"),
+        (VecOp::Div, false) => builder.build_int_signed_div(z_x, z_y, "vec_div"),
+    };
+
+    let mut result_elems: Vec<BasicValueEnum> = vec![];
+    let mut last = None;
+    for i in 0..width {
+        let idx = context.i64_type().const_int(i as u64, false);
+        let val = builder.build_extract_element(result, idx, "ext");
+        last = Some(val);
+        result_elems.push(val)
+    }
+
+    for (i, e) in result_elems.into_iter().enumerate() {
+        let idx = context.i64_type().const_int(i as u64, false);
+        let ptr = unsafe { builder.build_gep(z, &[idx], "gep") };
+        builder.build_store(ptr, e);
+    }
+
+    builder.build_return(Some(&last.unwrap()));
+
+    Some(function)
+}
+
 struct CodeGen<'ctx> {
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
     execution_engine: ExecutionEngine<'ctx>,
+    env: Env<String, BasicValueEnum<'ctx>>,
 }
 
 impl<'ctx> CodeGen<'ctx> {
-    fn jit_compile_sum(&self) -> Option<JitFunction<SumFunc>> {
+    fn jit_compile_sum(&self) -> Option<JitFunction<'_, SumFunc>> {
         let i64_type = self.context.i64_type();
         let fn_type = i64_type.fn_type(&[i64_type.into(), i64_type.into(), i64_type.into()], false);
         let function = self.module.add_function("sum", fn_type, None);
@@ -40,69 +203,520 @@ impl<'ctx> CodeGen<'ctx> {
         unsafe { self.execution_engine.get_function("sum").ok() }
     }
 
-    fn sum(
+    /// JIT `build_vector_kernel` and return the variant matching `elem_ty`.
+    ///
+    /// Builds into a fresh `Module` registered with `execution_engine` rather
+    /// than `self.module`: `get_function` finalizes the *entire* module it
+    /// looks a symbol up in, so anything added to `self.module` afterwards
+    /// would never be compiled. A private module per JIT call keeps each
+    /// lookup's finalization scoped to just that call.
+    fn jit_vector_kernel(
         &self,
-    ) -> Option<JitFunction<unsafe extern "C" fn(*const f64, *const f64, *mut f64) -> f64>> {
-        let width = 4;
+        name: &str,
+        op: VecOp,
+        elem_ty: ElemTy,
+        width: u32,
+    ) -> Option<VectorKernelFn<'ctx>> {
+        let module = self.context.create_module(name);
+        self.execution_engine.add_module(&module).ok()?;
+        build_vector_kernel(self.context, &module, &self.builder, name, op, elem_ty, width)?;
+        module.print_to_stderr();
+
+        unsafe {
+            match elem_ty {
+                ElemTy::F32 => self
+                    .execution_engine
+                    .get_function(name)
+                    .ok()
+                    .map(VectorKernelFn::F32),
+                ElemTy::F64 => self
+                    .execution_engine
+                    .get_function(name)
+                    .ok()
+                    .map(VectorKernelFn::F64),
+                ElemTy::I32 => self
+                    .execution_engine
+                    .get_function(name)
+                    .ok()
+                    .map(VectorKernelFn::I32),
+                ElemTy::I64 => self
+                    .execution_engine
+                    .get_function(name)
+                    .ok()
+                    .map(VectorKernelFn::I64),
+            }
+        }
+    }
 
-        let f64_4_ty = self.context.f64_type().vec_type(width);
-        let f64_ptr_ty = self.context.f64_type().ptr_type(AddressSpace::Generic);
-        let fn_type = self.context.f64_type().fn_type(
-            &[f64_ptr_ty.into(), f64_ptr_ty.into(), f64_ptr_ty.into()],
+    /// Vectorized `z[i] = x[i] + y[i]` over a runtime `len`, with a scalar
+    /// tail loop for the `len % width` remainder.
+    ///
+    /// Builds into a fresh `Module` for the same reason as
+    /// `jit_vector_kernel`: `self.module` would already be finalized by an
+    /// earlier `get_function` call by the time this runs.
+    fn jit_sum_n(&self, width: u32) -> Option<JitFunction<'ctx, SumNFunc>> {
+        let module = self.context.create_module("sum_n");
+        self.execution_engine.add_module(&module).ok()?;
+
+        let i64_ty = self.context.i64_type();
+        let f64_ty = self.context.f64_type();
+        let f64_ptr_ty = f64_ty.ptr_type(AddressSpace::default());
+        let vec_ty = f64_ty.vec_type(width);
+        let vec_ptr_ty = vec_ty.ptr_type(AddressSpace::default());
+        let void_ty = self.context.void_type();
+
+        let fn_type = void_ty.fn_type(
+            &[
+                f64_ptr_ty.into(),
+                f64_ptr_ty.into(),
+                f64_ptr_ty.into(),
+                i64_ty.into(),
+            ],
             false,
         );
-        let function = self.module.add_function("sum", fn_type, None);
-        let basic_block = self.context.append_basic_block(function, "entry");
+        let function = module.add_function("sum_n", fn_type, None);
 
-        self.builder.position_at_end(basic_block);
+        let entry_bb = self.context.append_basic_block(function, "entry");
+        let vec_header_bb = self.context.append_basic_block(function, "vec_header");
+        let vec_body_bb = self.context.append_basic_block(function, "vec_body");
+        let tail_header_bb = self.context.append_basic_block(function, "tail_header");
+        let tail_body_bb = self.context.append_basic_block(function, "tail_body");
+        let exit_bb = self.context.append_basic_block(function, "exit");
 
         let x = function.get_nth_param(0)?.into_pointer_value();
         let y = function.get_nth_param(1)?.into_pointer_value();
         let z = function.get_nth_param(2)?.into_pointer_value();
+        let len = function.get_nth_param(3)?.into_int_value();
+
+        self.builder.position_at_end(entry_bb);
+        let width_const = i64_ty.const_int(width as u64, false);
+        let remainder = self.builder.build_int_unsigned_rem(len, width_const, "rem");
+        let aligned_len = self.builder.build_int_sub(len, remainder, "aligned_len");
+        self.builder.build_unconditional_branch(vec_header_bb);
+
+        self.builder.position_at_end(vec_header_bb);
+        let vec_i = self.builder.build_phi(i64_ty, "vec_i");
+        vec_i.add_incoming(&[(&i64_ty.const_zero(), entry_bb)]);
+        let vec_i_int = vec_i.as_basic_value().into_int_value();
+        let vec_cond =
+            self.builder
+                .build_int_compare(IntPredicate::ULT, vec_i_int, aligned_len, "vec_cond");
+        self.builder
+            .build_conditional_branch(vec_cond, vec_body_bb, tail_header_bb);
+
+        self.builder.position_at_end(vec_body_bb);
+        let x_ptr = unsafe { self.builder.build_gep(x, &[vec_i_int], "x_gep") };
+        let y_ptr = unsafe { self.builder.build_gep(y, &[vec_i_int], "y_gep") };
+        let z_ptr = unsafe { self.builder.build_gep(z, &[vec_i_int], "z_gep") };
+        let x_vec_ptr = self
+            .builder
+            .build_bitcast(x_ptr, vec_ptr_ty, "x_vec_ptr")
+            .into_pointer_value();
+        let y_vec_ptr = self
+            .builder
+            .build_bitcast(y_ptr, vec_ptr_ty, "y_vec_ptr")
+            .into_pointer_value();
+        let z_vec_ptr = self
+            .builder
+            .build_bitcast(z_ptr, vec_ptr_ty, "z_vec_ptr")
+            .into_pointer_value();
+        // `x`/`y`/`z` are only 8-byte-aligned `f64` arrays, but the bitcast
+        // pointers above are typed as `<4 x f64>*`; without an explicit
+        // alignment override the builder would assume the vector type's
+        // natural (32-byte) alignment and fault on unaligned memory.
+        let x_vec_val = self.builder.build_load(x_vec_ptr, "x_vec");
+        x_vec_val.as_instruction_value().unwrap().set_alignment(8).unwrap();
+        let y_vec_val = self.builder.build_load(y_vec_ptr, "y_vec");
+        y_vec_val.as_instruction_value().unwrap().set_alignment(8).unwrap();
+        let x_vec = x_vec_val.into_vector_value();
+        let y_vec = y_vec_val.into_vector_value();
+        let sum_vec = self.builder.build_float_add(x_vec, y_vec, "sum_vec");
+        self.builder
+            .build_store(z_vec_ptr, sum_vec)
+            .set_alignment(8)
+            .unwrap();
+        let vec_i_next = self
+            .builder
+            .build_int_add(vec_i_int, width_const, "vec_i_next");
+        self.builder.build_unconditional_branch(vec_header_bb);
+        vec_i.add_incoming(&[(&vec_i_next, vec_body_bb)]);
+
+        self.builder.position_at_end(tail_header_bb);
+        let tail_i = self.builder.build_phi(i64_ty, "tail_i");
+        tail_i.add_incoming(&[(&aligned_len, vec_header_bb)]);
+        let tail_i_int = tail_i.as_basic_value().into_int_value();
+        let tail_cond = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, tail_i_int, len, "tail_cond");
+        self.builder
+            .build_conditional_branch(tail_cond, tail_body_bb, exit_bb);
+
+        self.builder.position_at_end(tail_body_bb);
+        let x_ptr = unsafe { self.builder.build_gep(x, &[tail_i_int], "x_gep") };
+        let y_ptr = unsafe { self.builder.build_gep(y, &[tail_i_int], "y_gep") };
+        let z_ptr = unsafe { self.builder.build_gep(z, &[tail_i_int], "z_gep") };
+        let x_val = self.builder.build_load(x_ptr, "x_val").into_float_value();
+        let y_val = self.builder.build_load(y_ptr, "y_val").into_float_value();
+        let sum_val = self.builder.build_float_add(x_val, y_val, "sum_val");
+        self.builder.build_store(z_ptr, sum_val);
+        let tail_i_next = self
+            .builder
+            .build_int_add(tail_i_int, i64_ty.const_int(1, false), "tail_i_next");
+        self.builder.build_unconditional_branch(tail_header_bb);
+        tail_i.add_incoming(&[(&tail_i_next, tail_body_bb)]);
+
+        self.builder.position_at_end(exit_bb);
+        self.builder.build_return(None);
+        module.print_to_stderr();
+
+        unsafe { self.execution_engine.get_function("sum_n").ok() }
+    }
+
+    /// Look up the inkwell type a name like `"int32"` or `"ptr<float64>"`
+    /// denotes; see `types::parse_basic_type`.
+    fn basic_type(&self, name: &str) -> Option<BasicTypeEnum<'ctx>> {
+        types::parse_basic_type(self.context, name)
+    }
+
+    /// Declare a function, resolving each type by name through `basic_type`.
+    fn declare_function(
+        &self,
+        name: &str,
+        param_type_names: &[&str],
+        ret_type_name: &str,
+    ) -> Option<FunctionValue<'ctx>> {
+        let ret_ty = self.basic_type(ret_type_name)?;
+        let param_tys = param_type_names
+            .iter()
+            .map(|n| self.basic_type(n).map(BasicMetadataTypeEnum::from))
+            .collect::<Option<Vec<BasicMetadataTypeEnum>>>()?;
 
-        let mut x_vals = vec![];
-        let mut y_vals = vec![];
-        for i in 0..width {
-            let idx = self.context.i64_type().const_int(i as u64, false);
-            let x_ptr = unsafe { self.builder.build_gep(x, &[idx], "gep") };
-            let x_val = self.builder.build_load(x_ptr, "load");
-            let y_ptr = unsafe { self.builder.build_gep(y, &[idx], "gep") };
-            let y_val = self.builder.build_load(y_ptr, "load");
-            x_vals.push((idx, x_val));
-            y_vals.push((idx, y_val))
+        let fn_type = ret_ty.fn_type(&param_tys, false);
+        Some(self.module.add_function(name, fn_type, None))
+    }
+
+    /// Serialize the module to LLVM bitcode.
+    fn write_bitcode(&self) -> Vec<u8> {
+        self.module.write_bitcode_to_memory().as_slice().to_vec()
+    }
+
+    /// Compile the module to a relocatable object file at `path`. `triple`
+    /// defaults to the host target when `None`; a non-host `triple` only
+    /// initializes that one target's backend, not the host CPU's name or
+    /// feature set, which wouldn't apply to it.
+    fn emit_object_file(&self, triple: Option<&str>, path: &Path) -> Result<(), Box<dyn Error>> {
+        let (triple, cpu_name, cpu_features) = match triple {
+            Some(triple) => {
+                Target::initialize_all(&InitializationConfig::default());
+                (TargetTriple::create(triple), String::new(), String::new())
+            }
+            None => {
+                Target::initialize_native(&InitializationConfig::default())?;
+                (
+                    TargetMachine::get_default_triple(),
+                    TargetMachine::get_host_cpu_name().to_string(),
+                    TargetMachine::get_host_cpu_features().to_string(),
+                )
+            }
+        };
+        let target = Target::from_triple(&triple)?;
+        let target_machine = target
+            .create_target_machine(
+                &triple,
+                &cpu_name,
+                &cpu_features,
+                OptimizationLevel::Default,
+                RelocMode::Default,
+                CodeModel::Default,
+            )
+            .ok_or("Unable to create target machine for the requested triple")?;
+
+        target_machine.write_to_file(&self.module, FileType::Object, path)?;
+
+        Ok(())
+    }
+
+    /// Fan `jobs` out across `threads` worker threads, each with its own
+    /// `Context` and `Module` (inkwell's `Context` isn't `Send`). `on_module`
+    /// runs on each finished module before it's serialized to bitcode.
+    fn compile_parallel(
+        jobs: Vec<KernelSpec>,
+        threads: usize,
+        on_module: impl Fn(&Module) + Send + Sync + 'static,
+    ) -> Vec<Vec<u8>> {
+        let threads = threads.max(1);
+        let jobs = Arc::new(jobs);
+        let on_module = Arc::new(on_module);
+        let bitcodes = Arc::new(Mutex::new(Vec::with_capacity(threads)));
+
+        let handles: Vec<_> = (0..threads)
+            .map(|thread_idx| {
+                let jobs = Arc::clone(&jobs);
+                let on_module = Arc::clone(&on_module);
+                let bitcodes = Arc::clone(&bitcodes);
+
+                thread::spawn(move || {
+                    let context = Context::create();
+                    let module = context.create_module(&format!("kernels_{}", thread_idx));
+                    let builder = context.create_builder();
+
+                    for (i, job) in jobs.iter().enumerate() {
+                        if i % threads != thread_idx {
+                            continue;
+                        }
+                        build_vector_kernel(
+                            &context, &module, &builder, &job.name, job.op, job.elem_ty,
+                            job.width,
+                        )
+                        .expect("build_vector_kernel failed for a KernelSpec in compile_parallel");
+                    }
+
+                    on_module(&module);
+                    let bitcode = module.write_bitcode_to_memory().as_slice().to_vec();
+                    bitcodes.lock().unwrap().push(bitcode);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().expect("kernel codegen thread panicked");
         }
 
-        let mut z_x = f64_4_ty.const_zero();
-        for (i, x) in x_vals {
-            z_x = self.builder.build_insert_element(z_x, x, i, "insert");
+        Arc::try_unwrap(bitcodes)
+            .expect("all worker threads have joined")
+            .into_inner()
+            .expect("bitcode mutex was not poisoned")
+    }
+
+    /// Lower an `Expr` into the instruction(s) for its value.
+    fn compile_expr(&mut self, expr: &Expr) -> BasicValueEnum<'ctx> {
+        match expr {
+            Expr::IntLit(v) => self.context.i64_type().const_int(*v as u64, true).into(),
+            Expr::FloatLit(v) => self.context.f64_type().const_float(*v).into(),
+            Expr::Var(name) => *self
+                .env
+                .get(name)
+                .unwrap_or_else(|| panic!("undefined variable `{}`", name)),
+            Expr::BinOp(op, lhs, rhs) => self.compile_binop(*op, lhs, rhs),
+            Expr::If(cond, then_branch, else_branch) => {
+                self.compile_if(cond, then_branch, else_branch)
+            }
+            Expr::Let(name, value, body) => self.compile_let(name, value, body),
         }
+    }
 
-        let mut z_y = f64_4_ty.const_zero();
-        for (i, x) in y_vals {
-            z_y = self.builder.build_insert_element(z_y, x, i, "insert");
+    /// Coerce an int-typed operand to `f64` so it can be mixed with a float
+    /// operand (e.g. the literal `1` in `a + 1` where `a` is an `f64`
+    /// variable). Float operands pass through unchanged.
+    fn coerce_to_float(&self, val: BasicValueEnum<'ctx>) -> inkwell::values::FloatValue<'ctx> {
+        if val.is_int_value() {
+            let int_val = val.into_int_value();
+            assert_eq!(
+                int_val.get_type().get_bit_width(),
+                64,
+                "cannot mix a non-i64 int (e.g. a comparison's i1 result) with a float operand"
+            );
+            self.builder
+                .build_signed_int_to_float(int_val, self.context.f64_type(), "itof")
+        } else {
+            val.into_float_value()
         }
+    }
 
-        let add = self.builder.build_float_add(z_x, z_y, "vec_add");
+    fn compile_binop(&mut self, op: BinOp, lhs: &Expr, rhs: &Expr) -> BasicValueEnum<'ctx> {
+        let lhs_val = self.compile_expr(lhs);
+        let rhs_val = self.compile_expr(rhs);
 
-        let mut add_elems = vec![];
-        let mut a = None;
-        for i in 0..width {
-            let idx = self.context.i64_type().const_int(i as u64, false);
-            let val = self.builder.build_extract_element(add, idx, "ext");
-            a = Some(val);
-            add_elems.push(val)
+        if lhs_val.is_float_value() || rhs_val.is_float_value() {
+            let lhs_val = self.coerce_to_float(lhs_val);
+            let rhs_val = self.coerce_to_float(rhs_val);
+            match op {
+                BinOp::Add => self.builder.build_float_add(lhs_val, rhs_val, "fadd").into(),
+                BinOp::Sub => self.builder.build_float_sub(lhs_val, rhs_val, "fsub").into(),
+                BinOp::Mul => self.builder.build_float_mul(lhs_val, rhs_val, "fmul").into(),
+                BinOp::Div => self.builder.build_float_div(lhs_val, rhs_val, "fdiv").into(),
+                BinOp::Lt => self
+                    .builder
+                    .build_float_compare(FloatPredicate::OLT, lhs_val, rhs_val, "flt")
+                    .into(),
+                BinOp::Le => self
+                    .builder
+                    .build_float_compare(FloatPredicate::OLE, lhs_val, rhs_val, "fle")
+                    .into(),
+                BinOp::Gt => self
+                    .builder
+                    .build_float_compare(FloatPredicate::OGT, lhs_val, rhs_val, "fgt")
+                    .into(),
+                BinOp::Ge => self
+                    .builder
+                    .build_float_compare(FloatPredicate::OGE, lhs_val, rhs_val, "fge")
+                    .into(),
+                BinOp::Eq => self
+                    .builder
+                    .build_float_compare(FloatPredicate::OEQ, lhs_val, rhs_val, "feq")
+                    .into(),
+                BinOp::Ne => self
+                    .builder
+                    .build_float_compare(FloatPredicate::ONE, lhs_val, rhs_val, "fne")
+                    .into(),
+            }
+        } else {
+            let lhs_val = lhs_val.into_int_value();
+            let rhs_val = rhs_val.into_int_value();
+            match op {
+                BinOp::Add => self.builder.build_int_add(lhs_val, rhs_val, "iadd").into(),
+                BinOp::Sub => self.builder.build_int_sub(lhs_val, rhs_val, "isub").into(),
+                BinOp::Mul => self.builder.build_int_mul(lhs_val, rhs_val, "imul").into(),
+                BinOp::Div => self
+                    .builder
+                    .build_int_signed_div(lhs_val, rhs_val, "idiv")
+                    .into(),
+                BinOp::Lt => self
+                    .builder
+                    .build_int_compare(IntPredicate::SLT, lhs_val, rhs_val, "ilt")
+                    .into(),
+                BinOp::Le => self
+                    .builder
+                    .build_int_compare(IntPredicate::SLE, lhs_val, rhs_val, "ile")
+                    .into(),
+                BinOp::Gt => self
+                    .builder
+                    .build_int_compare(IntPredicate::SGT, lhs_val, rhs_val, "igt")
+                    .into(),
+                BinOp::Ge => self
+                    .builder
+                    .build_int_compare(IntPredicate::SGE, lhs_val, rhs_val, "ige")
+                    .into(),
+                BinOp::Eq => self
+                    .builder
+                    .build_int_compare(IntPredicate::EQ, lhs_val, rhs_val, "ieq")
+                    .into(),
+                BinOp::Ne => self
+                    .builder
+                    .build_int_compare(IntPredicate::NE, lhs_val, rhs_val, "ine")
+                    .into(),
+            }
         }
+    }
+
+    fn compile_if(
+        &mut self,
+        cond: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> BasicValueEnum<'ctx> {
+        let function = self
+            .builder
+            .get_insert_block()
+            .and_then(|bb| bb.get_parent())
+            .expect("compile_if requires the builder to be positioned inside a function");
+
+        let cond_val = self.compile_expr(cond).into_int_value();
+        assert_eq!(
+            cond_val.get_type().get_bit_width(),
+            1,
+            "if condition must be a boolean (i1), got a {}-bit int",
+            cond_val.get_type().get_bit_width()
+        );
+
+        let then_bb = self.context.append_basic_block(function, "then");
+        let else_bb = self.context.append_basic_block(function, "else");
+        let merge_bb = self.context.append_basic_block(function, "merge");
+
+        self.builder
+            .build_conditional_branch(cond_val, then_bb, else_bb);
+
+        self.builder.position_at_end(then_bb);
+        let then_val = self.compile_expr(then_branch);
+        let then_bb = self.builder.get_insert_block().unwrap();
+
+        self.builder.position_at_end(else_bb);
+        let else_val = self.compile_expr(else_branch);
+        let else_bb = self.builder.get_insert_block().unwrap();
+
+        // Mirror compile_binop's int/float coercion: a literal on one branch
+        // and an f64 on the other (e.g. `if x < 0.0 then 0 else x`) is the
+        // common case, not a type error, so coerce before the phi rather
+        // than rejecting it outright.
+        let needs_float = then_val.is_float_value() || else_val.is_float_value();
+
+        self.builder.position_at_end(then_bb);
+        let then_val: BasicValueEnum<'ctx> = if needs_float {
+            self.coerce_to_float(then_val).into()
+        } else {
+            then_val
+        };
+        self.builder.build_unconditional_branch(merge_bb);
+
+        self.builder.position_at_end(else_bb);
+        let else_val: BasicValueEnum<'ctx> = if needs_float {
+            self.coerce_to_float(else_val).into()
+        } else {
+            else_val
+        };
+        self.builder.build_unconditional_branch(merge_bb);
+
+        self.builder.position_at_end(merge_bb);
+        assert_eq!(
+            then_val.get_type(),
+            else_val.get_type(),
+            "if/else branches must produce the same type, got {:?} and {:?}",
+            then_val.get_type(),
+            else_val.get_type()
+        );
+        let phi = self.builder.build_phi(then_val.get_type(), "if_result");
+        phi.add_incoming(&[(&then_val, then_bb), (&else_val, else_bb)]);
+        phi.as_basic_value()
+    }
+
+    fn compile_let(&mut self, name: &str, value: &Expr, body: &Expr) -> BasicValueEnum<'ctx> {
+        let value = self.compile_expr(value);
+        self.env.push_scope();
+        self.env.define(name.to_string(), value);
+        let result = self.compile_expr(body);
+        self.env.pop_scope();
+        result
+    }
 
-        for (i, e) in add_elems.into_iter().enumerate() {
-            let idx = self.context.i64_type().const_int(i as u64, false);
-            let ptr = unsafe { self.builder.build_gep(z, &[idx], "gep") };
-            self.builder.build_store(ptr, e);
+    /// Demo entry point: JIT a two-argument `f64` function from an `Expr`,
+    /// binding `params` to the function's arguments before lowering `body`.
+    ///
+    /// Builds into a fresh `Module` for the same reason as
+    /// `jit_vector_kernel`: `self.module` would already be finalized by an
+    /// earlier `get_function` call by the time this runs.
+    fn jit_compile_expr_fn(
+        &mut self,
+        name: &str,
+        params: &[&str],
+        body: &Expr,
+    ) -> Option<JitFunction<'ctx, unsafe extern "C" fn(f64, f64) -> f64>> {
+        let module = self.context.create_module(name);
+        self.execution_engine.add_module(&module).ok()?;
+
+        let f64_ty = self.context.f64_type();
+        let fn_type = f64_ty.fn_type(&[f64_ty.into(), f64_ty.into()], false);
+        let function = module.add_function(name, fn_type, None);
+        let basic_block = self.context.append_basic_block(function, "entry");
+
+        self.builder.position_at_end(basic_block);
+
+        let bound_params = params
+            .iter()
+            .enumerate()
+            .map(|(i, param_name)| Some((param_name.to_string(), function.get_nth_param(i as u32)?)))
+            .collect::<Option<Vec<_>>>()?;
+
+        self.env.push_scope();
+        for (param_name, param) in bound_params {
+            self.env.define(param_name, param);
         }
+        let result = self.compile_expr(body);
+        self.env.pop_scope();
 
-        self.builder.build_return(Some(&a.unwrap()));
-        self.module.print_to_stderr();
+        self.builder.build_return(Some(&result));
+        module.print_to_stderr();
 
-        unsafe { self.execution_engine.get_function("sum").ok() }
+        unsafe { self.execution_engine.get_function(name).ok() }
     }
 }
 
@@ -110,17 +724,24 @@ fn main() -> Result<(), Box<dyn Error>> {
     let context = Context::create();
     let module = context.create_module("sum");
     let execution_engine = module.create_jit_execution_engine(OptimizationLevel::Aggressive)?;
-    let codegen = CodeGen {
+    let mut codegen = CodeGen {
         context: &context,
         module,
         builder: context.create_builder(),
         execution_engine,
+        env: Env::new(),
     };
 
     // let sum = codegen
     //     .jit_compile_sum()
     //     .ok_or("Unable to JIT compile `sum`")?;
-    let sum2 = codegen.sum().ok_or("Unable to JIT compile `sum`")?;
+    let sum2 = codegen
+        .jit_vector_kernel("vector_kernel", VecOp::Add, ElemTy::F64, 4)
+        .ok_or("Unable to JIT compile `vector_kernel`")?;
+    let sum2 = match sum2 {
+        VectorKernelFn::F64(f) => f,
+        _ => unreachable!(),
+    };
 
     let x = [1f64, 2f64, 3f64, 4f64];
     let y = [1f64, 2f64, 3f64, 4f64];
@@ -134,5 +755,96 @@ fn main() -> Result<(), Box<dyn Error>> {
         assert_eq!(z[3], 8f64);
     }
 
+    let sum_n = codegen
+        .jit_sum_n(4)
+        .ok_or("Unable to JIT compile `sum_n`")?;
+
+    let x = [1f64, 2f64, 3f64, 4f64, 5f64, 6f64];
+    let y = [1f64, 2f64, 3f64, 4f64, 5f64, 6f64];
+    let mut z = [0f64; 6];
+
+    unsafe {
+        sum_n.call(x.as_ptr(), y.as_ptr(), z.as_mut_ptr(), x.len() as u64);
+        assert_eq!(z, [2f64, 4f64, 6f64, 8f64, 10f64, 12f64]);
+    }
+
+    // let max(a, b) = if a > b then a else b
+    let max_expr = Expr::Let(
+        "result".to_string(),
+        Box::new(Expr::If(
+            Box::new(Expr::BinOp(
+                BinOp::Gt,
+                Box::new(Expr::Var("a".to_string())),
+                Box::new(Expr::Var("b".to_string())),
+            )),
+            Box::new(Expr::Var("a".to_string())),
+            Box::new(Expr::Var("b".to_string())),
+        )),
+        Box::new(Expr::Var("result".to_string())),
+    );
+    let max_fn = codegen
+        .jit_compile_expr_fn("max", &["a", "b"], &max_expr)
+        .ok_or("Unable to JIT compile `max`")?;
+
+    unsafe {
+        assert_eq!(max_fn.call(3.0, 7.0), 7.0);
+        assert_eq!(max_fn.call(7.0, 3.0), 7.0);
+    }
+
+    // Assemble a signature at runtime via the type registry, then fill in
+    // its body by hand.
+    let triple_add_fn = codegen
+        .declare_function("triple_add", &["int64", "int64", "int64"], "int64")
+        .ok_or("Unable to declare `triple_add`")?;
+    let entry = codegen.context.append_basic_block(triple_add_fn, "entry");
+    codegen.builder.position_at_end(entry);
+    let a = triple_add_fn.get_nth_param(0).unwrap().into_int_value();
+    let b = triple_add_fn.get_nth_param(1).unwrap().into_int_value();
+    let c = triple_add_fn.get_nth_param(2).unwrap().into_int_value();
+    let sum = codegen.builder.build_int_add(a, b, "sum");
+    let sum = codegen.builder.build_int_add(sum, c, "sum");
+    codegen.builder.build_return(Some(&sum));
+
+    let triple_add = unsafe {
+        codegen
+            .execution_engine
+            .get_function::<unsafe extern "C" fn(i64, i64, i64) -> i64>("triple_add")?
+    };
+
+    unsafe {
+        assert_eq!(triple_add.call(1, 2, 3), 6);
+    }
+
+    // Emit the module both as bitcode and as a native object file, for
+    // consumers that want to link the kernels into another program instead
+    // of JIT-ing them in-process.
+    let _bitcode = codegen.write_bitcode();
+    codegen.emit_object_file(None, Path::new("kernels.o"))?;
+
+    // Fan a batch of kernels out across worker threads, each with its own
+    // Context and Module, and collect the resulting bitcode.
+    let jobs = vec![
+        KernelSpec {
+            name: "vec_add_f64x4".to_string(),
+            op: VecOp::Add,
+            elem_ty: ElemTy::F64,
+            width: 4,
+        },
+        KernelSpec {
+            name: "vec_mul_f32x8".to_string(),
+            op: VecOp::Mul,
+            elem_ty: ElemTy::F32,
+            width: 8,
+        },
+        KernelSpec {
+            name: "vec_sub_i32x4".to_string(),
+            op: VecOp::Sub,
+            elem_ty: ElemTy::I32,
+            width: 4,
+        },
+    ];
+    let bitcodes = CodeGen::compile_parallel(jobs, 2, |module| module.print_to_stderr());
+    assert_eq!(bitcodes.len(), 2);
+
     Ok(())
 }
@@ -0,0 +1,23 @@
+use inkwell::context::Context;
+use inkwell::types::{BasicType, BasicTypeEnum};
+use inkwell::AddressSpace;
+
+/// Parse a type name into the inkwell `BasicTypeEnum` it denotes.
+///
+/// Recognizes `"int32"`, `"int64"`, `"float32"`, `"float64"`, and
+/// `"ptr<T>"` for any type name `T` this function itself recognizes (so
+/// `"ptr<ptr<float64>>"` works too). Returns `None` for anything else.
+pub fn parse_basic_type<'ctx>(context: &'ctx Context, name: &str) -> Option<BasicTypeEnum<'ctx>> {
+    if let Some(inner) = name.strip_prefix("ptr<").and_then(|rest| rest.strip_suffix('>')) {
+        let inner_ty = parse_basic_type(context, inner)?;
+        return Some(inner_ty.ptr_type(AddressSpace::default()).into());
+    }
+
+    match name {
+        "int32" => Some(context.i32_type().into()),
+        "int64" => Some(context.i64_type().into()),
+        "float32" => Some(context.f32_type().into()),
+        "float64" => Some(context.f64_type().into()),
+        _ => None,
+    }
+}